@@ -0,0 +1,68 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cross-platform clipboard access (copy/paste of text and other formats), used e.g. so the
+//! DesktopUI can place a ZeroTier network-join link on the clipboard, or notice when the user
+//! has copied one.
+//!
+//! [`Clipboard`] is a platform-selected re-export: on each OS it's the backend in
+//! `platform_impl` for that OS, so call sites are the same everywhere even though the
+//! implementations (Win32, `NSPasteboard`, X11) are completely different.
+
+use std::borrow::Cow;
+
+#[cfg(target_os = "windows")]
+use crate::platform_impl::windows::clipboard as platform;
+#[cfg(target_os = "macos")]
+use crate::platform_impl::macos::clipboard as platform;
+#[cfg(any(
+  target_os = "linux",
+  target_os = "dragonfly",
+  target_os = "freebsd",
+  target_os = "netbsd",
+  target_os = "openbsd"
+))]
+use crate::platform_impl::linux::clipboard as platform;
+
+pub use platform::{Clipboard, ClipboardError};
+
+/// Identifies a clipboard format: a standard one like [`ClipboardFormat::TEXT`], or a
+/// platform-registered custom identifier (e.g. a ZeroTier-specific MIME type).
+pub type FormatId = &'static str;
+
+/// A single piece of clipboard data together with the format it's encoded in.
+#[derive(Debug, Clone)]
+pub struct ClipboardFormat {
+  pub identifier: FormatId,
+  pub data: Cow<'static, [u8]>,
+}
+
+impl ClipboardFormat {
+  /// Plain UTF-8 text.
+  pub const TEXT: FormatId = "public.utf8-plain-text";
+
+  pub fn new(identifier: FormatId, data: impl Into<Cow<'static, [u8]>>) -> Self {
+    ClipboardFormat {
+      identifier,
+      data: data.into(),
+    }
+  }
+}
+
+impl From<&str> for ClipboardFormat {
+  fn from(s: &str) -> Self {
+    ClipboardFormat::new(ClipboardFormat::TEXT, s.as_bytes().to_vec())
+  }
+}
+
+/// Operations every platform clipboard backend implements. [`Clipboard`] is whichever backend
+/// matches the current target OS; this trait exists so all three stay API-compatible and so
+/// generic code (tests, the tray app) can be written once against it.
+pub trait ClipboardBackend: Default {
+  type Error: std::error::Error;
+
+  fn write_text(&mut self, s: &str) -> Result<(), Self::Error>;
+  fn read_text(&self) -> Result<Option<String>, Self::Error>;
+  fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), Self::Error>;
+  fn available_formats(&self) -> Result<Vec<FormatId>, Self::Error>;
+}
@@ -0,0 +1,397 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+
+//! X11/Wayland (via XWayland) clipboard backend, implemented on the ICCCM selection-owner
+//! protocol: to offer data we become the owner of the `CLIPBOARD` selection and answer
+//! `SelectionRequest` events on our own hidden window; to read data we issue
+//! `XConvertSelection` and wait for the `SelectionNotify` that delivers it into a property on
+//! that window.
+//!
+//! Being a selection owner is a standing obligation, not a one-off call: another application
+//! can send us a `SelectionRequest` at any time for as long as we hold the selection, so a
+//! dedicated background thread pumps the X connection's event queue for the process lifetime
+//! and answers them as they arrive, instead of only servicing them incidentally while we happen
+//! to be in the middle of our own read.
+
+use crate::clipboard::{ClipboardBackend, ClipboardFormat, FormatId};
+use std::{
+  ffi::CString,
+  fmt, ptr,
+  sync::{mpsc, Mutex},
+  thread,
+  time::Duration,
+};
+use x11_dl::xlib::{self, Atom, Display, Window, Xlib};
+
+#[derive(Debug)]
+pub struct ClipboardError(String);
+
+impl fmt::Display for ClipboardError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for ClipboardError {}
+
+const CONVERT_SELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+struct Connection {
+  xlib: Xlib,
+  display: *mut Display,
+  window: Window,
+  clipboard: Atom,
+  targets: Atom,
+  incr: Atom,
+  utf8_string: Atom,
+  text_plain: Atom,
+  reply_property: Atom,
+}
+
+// `display`/`window`/the atoms are a plain connection handle, safe to share across threads now
+// that `Connection::open` calls `XInitThreads` before opening the display.
+unsafe impl Send for Connection {}
+
+impl Connection {
+  fn open() -> Result<Self, ClipboardError> {
+    unsafe {
+      let xlib =
+        Xlib::open().map_err(|e| ClipboardError(format!("failed to load libX11: {}", e)))?;
+      // We touch this connection from both the calling thread (XSetSelectionOwner,
+      // XConvertSelection, ...) and the background event-pump thread below, so Xlib needs to
+      // know up front that it must guard its internal state.
+      (xlib.XInitThreads)();
+
+      let display = (xlib.XOpenDisplay)(ptr::null());
+      if display.is_null() {
+        return Err(ClipboardError(
+          "failed to open a connection to the X server".into(),
+        ));
+      }
+
+      let screen = (xlib.XDefaultScreen)(display);
+      let root = (xlib.XRootWindow)(display, screen);
+      // A 1x1 window that's never mapped: it only exists to own the selection and receive the
+      // events the ICCCM protocol sends to a selection owner.
+      let window = (xlib.XCreateSimpleWindow)(display, root, 0, 0, 1, 1, 0, 0, 0);
+
+      let intern = |name: &str| -> Atom {
+        let cname = CString::new(name).unwrap();
+        (xlib.XInternAtom)(display, cname.as_ptr(), xlib::False)
+      };
+
+      Ok(Connection {
+        clipboard: intern("CLIPBOARD"),
+        targets: intern("TARGETS"),
+        incr: intern("INCR"),
+        utf8_string: intern("UTF8_STRING"),
+        text_plain: intern("text/plain;charset=utf-8"),
+        reply_property: intern("ZEROTIER_DESKTOPUI_CLIPBOARD_REPLY"),
+        xlib,
+        display,
+        window,
+      })
+    }
+  }
+}
+
+// The live X11 connection, lazily opened on first use and kept around for the process
+// lifetime, matching how every other clipboard backend here is a thin, cheap-to-construct
+// handle onto global OS clipboard state. `connection()` also starts the background
+// event-pump thread the first time it's called.
+static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+// The formats passed to the last `put_formats` while we own CLIPBOARD. Only the
+// `ClipboardFormat::TEXT` entry is served to SelectionRequest events; other formats are neither
+// advertised in TARGETS nor served.
+static HELD: Mutex<Vec<ClipboardFormat>> = Mutex::new(Vec::new());
+// The reply channel for whichever `convert_selection` call is currently waiting on a
+// SelectionNotify; filled in by the background event-pump thread when one arrives.
+static PENDING_NOTIFY: Mutex<Option<mpsc::Sender<Atom>>> = Mutex::new(None);
+// Caches the name we've leaked for each target atom `available_formats` has seen, so repeated
+// calls (e.g. a watcher inspecting formats on every clipboard change) don't re-leak it.
+static FORMAT_NAME_CACHE: Mutex<Vec<(Atom, FormatId)>> = Mutex::new(Vec::new());
+
+fn atom_name(conn: &Connection, atom: Atom) -> Option<FormatId> {
+  let mut cache = FORMAT_NAME_CACHE.lock().unwrap();
+  if let Some((_, name)) = cache.iter().find(|(cached, _)| *cached == atom) {
+    return Some(*name);
+  }
+
+  unsafe {
+    let raw = (conn.xlib.XGetAtomName)(conn.display, atom);
+    if raw.is_null() {
+      return None;
+    }
+    let name = std::ffi::CStr::from_ptr(raw).to_string_lossy().into_owned();
+    (conn.xlib.XFree)(raw as *mut _);
+    let name: FormatId = Box::leak(name.into_boxed_str());
+    cache.push((atom, name));
+    Some(name)
+  }
+}
+
+fn connection() -> Result<Connection, ClipboardError> {
+  let mut guard = CONNECTION.lock().unwrap();
+  if guard.is_none() {
+    let conn = Connection::open()?;
+    let event_thread_conn = conn.clone();
+    thread::spawn(move || run_event_thread(event_thread_conn));
+    *guard = Some(conn);
+  }
+  Ok(guard.as_ref().unwrap().clone())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clipboard;
+
+impl Clipboard {
+  pub fn write_text(&mut self, s: impl AsRef<str>) -> Result<(), ClipboardError> {
+    let format: ClipboardFormat = s.as_ref().into();
+    self.put_formats(&[format])
+  }
+
+  pub fn read_text(&self) -> Result<Option<String>, ClipboardError> {
+    self.read_format(ClipboardFormat::TEXT)
+  }
+
+  pub fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
+    let conn = connection()?;
+
+    unsafe {
+      (conn.xlib.XSetSelectionOwner)(conn.display, conn.clipboard, conn.window, xlib::CurrentTime);
+      (conn.xlib.XFlush)(conn.display);
+
+      let owner = (conn.xlib.XGetSelectionOwner)(conn.display, conn.clipboard);
+      if owner != conn.window {
+        return Err(ClipboardError(
+          "failed to become the CLIPBOARD selection owner".into(),
+        ));
+      }
+    }
+
+    *HELD.lock().unwrap() = formats.to_vec();
+    Ok(())
+  }
+
+  pub fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    let conn = connection()?;
+
+    let data = convert_selection(&conn, conn.targets)?;
+    let data = match data {
+      Some(data) => data,
+      None => return Ok(Vec::new()),
+    };
+
+    // TARGETS comes back as an array of Atom-sized (format-32, one C `long` each) values.
+    let atoms: &[Atom] = unsafe {
+      std::slice::from_raw_parts(
+        data.as_ptr() as *const Atom,
+        data.len() / std::mem::size_of::<Atom>(),
+      )
+    };
+    let mut names = Vec::with_capacity(atoms.len());
+    for &atom in atoms {
+      if let Some(name) = atom_name(&conn, atom) {
+        names.push(name);
+      }
+    }
+    Ok(names)
+  }
+
+  fn read_format(&self, identifier: FormatId) -> Result<Option<String>, ClipboardError> {
+    let conn = connection()?;
+
+    let target = if identifier == ClipboardFormat::TEXT {
+      conn.utf8_string
+    } else {
+      let cname = CString::new(identifier)
+        .map_err(|_| ClipboardError(format!("format '{}' contains a null byte", identifier)))?;
+      unsafe { (conn.xlib.XInternAtom)(conn.display, cname.as_ptr(), xlib::True) }
+    };
+    if target == 0 {
+      return Ok(None);
+    }
+
+    match convert_selection(&conn, target)? {
+      Some(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+      None => Ok(None),
+    }
+  }
+}
+
+// Requests `target` from whoever owns CLIPBOARD (possibly us, possibly another application) via
+// `XConvertSelection`, then waits for the background event-pump thread to hand us the resulting
+// `SelectionNotify` (or we time out), and reads the property it was delivered into.
+fn convert_selection(conn: &Connection, target: Atom) -> Result<Option<Vec<u8>>, ClipboardError> {
+  let (tx, rx) = mpsc::channel();
+  *PENDING_NOTIFY.lock().unwrap() = Some(tx);
+
+  unsafe {
+    (conn.xlib.XConvertSelection)(
+      conn.display,
+      conn.clipboard,
+      target,
+      conn.reply_property,
+      conn.window,
+      xlib::CurrentTime,
+    );
+    (conn.xlib.XFlush)(conn.display);
+  }
+
+  let property = match rx.recv_timeout(CONVERT_SELECTION_TIMEOUT) {
+    Ok(property) => property,
+    Err(_) => {
+      *PENDING_NOTIFY.lock().unwrap() = None;
+      return Err(ClipboardError(
+        "timed out waiting for the clipboard owner to respond".into(),
+      ));
+    }
+  };
+  if property == 0 {
+    // The owner declined to convert to this target (e.g. it doesn't have that format).
+    return Ok(None);
+  }
+
+  unsafe {
+    let (mut actual_type, mut actual_format, mut n_items, mut bytes_after) = (0, 0, 0, 0);
+    let mut data: *mut u8 = ptr::null_mut();
+    (conn.xlib.XGetWindowProperty)(
+      conn.display,
+      conn.window,
+      conn.reply_property,
+      0,
+      i32::MAX as i64,
+      xlib::False,
+      xlib::AnyPropertyType as u64,
+      &mut actual_type,
+      &mut actual_format,
+      &mut n_items,
+      &mut bytes_after,
+      &mut data,
+    );
+    if data.is_null() || actual_type == conn.incr {
+      // Large (INCR-chunked) transfers aren't supported; treat as unavailable rather than
+      // hanging or misreading a partial property.
+      if !data.is_null() {
+        (conn.xlib.XFree)(data as *mut _);
+      }
+      return Ok(None);
+    }
+
+    // Xlib always delivers format-32 properties as an array of the platform's C `long` (8
+    // bytes on a 64-bit target), never as packed 4-byte values, even though the atom/XID
+    // values it carries only need 32 bits; format 8/16 properties are packed at their
+    // nominal byte width.
+    let byte_len = if actual_format == 32 {
+      n_items as usize * std::mem::size_of::<std::os::raw::c_ulong>()
+    } else {
+      n_items as usize * (actual_format as usize / 8)
+    };
+    let bytes = std::slice::from_raw_parts(data, byte_len).to_vec();
+    (conn.xlib.XFree)(data as *mut _);
+    Ok(Some(bytes))
+  }
+}
+
+// Runs for the lifetime of the process once the clipboard has been touched at all, answering
+// SelectionRequest/SelectionClear as they arrive so another application can paste from us (or
+// take ownership away) at any time, not only while we happen to be mid-read ourselves.
+fn run_event_thread(conn: Connection) {
+  loop {
+    let mut event: xlib::XEvent = unsafe { std::mem::zeroed() };
+    unsafe { (conn.xlib.XNextEvent)(conn.display, &mut event) };
+    match event.get_type() {
+      xlib::SelectionRequest => unsafe {
+        respond_to_selection_request(&conn, &event.selection_request)
+      },
+      xlib::SelectionClear => HELD.lock().unwrap().clear(),
+      xlib::SelectionNotify => {
+        let property = unsafe { event.selection }.property;
+        if let Some(tx) = PENDING_NOTIFY.lock().unwrap().take() {
+          let _ = tx.send(property);
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+unsafe fn respond_to_selection_request(conn: &Connection, request: &xlib::XSelectionRequestEvent) {
+  let mut response = xlib::XSelectionEvent {
+    type_: xlib::SelectionNotify,
+    serial: 0,
+    send_event: xlib::True,
+    display: conn.display,
+    requestor: request.requestor,
+    selection: request.selection,
+    target: request.target,
+    property: 0,
+    time: request.time,
+  };
+
+  let held = HELD.lock().unwrap();
+
+  if request.target == conn.targets {
+    // COMPOUND_TEXT is deliberately not offered here: it's a distinct (non-UTF-8) encoding,
+    // and we only have the data as UTF-8 text, so advertising it would mislead requestors
+    // (mostly older GTK/Qt apps) that specifically ask for it into misdecoding the bytes.
+    let mut offered = vec![conn.targets, conn.utf8_string, conn.text_plain];
+    offered.dedup();
+    (conn.xlib.XChangeProperty)(
+      conn.display,
+      request.requestor,
+      request.property,
+      xlib::XA_ATOM,
+      32,
+      xlib::PropModeReplace,
+      offered.as_ptr() as *const u8,
+      offered.len() as i32,
+    );
+    response.property = request.property;
+  } else if request.target == conn.utf8_string || request.target == conn.text_plain {
+    if let Some(format) = held.iter().find(|f| f.identifier == ClipboardFormat::TEXT) {
+      (conn.xlib.XChangeProperty)(
+        conn.display,
+        request.requestor,
+        request.property,
+        request.target,
+        8,
+        xlib::PropModeReplace,
+        format.data.as_ptr(),
+        format.data.len() as i32,
+      );
+      response.property = request.property;
+    }
+  }
+
+  let mut response_event = xlib::XEvent { selection: response };
+  (conn.xlib.XSendEvent)(
+    conn.display,
+    request.requestor,
+    xlib::False,
+    0,
+    &mut response_event,
+  );
+  (conn.xlib.XFlush)(conn.display);
+}
+
+impl ClipboardBackend for Clipboard {
+  type Error = ClipboardError;
+
+  fn write_text(&mut self, s: &str) -> Result<(), ClipboardError> {
+    Clipboard::write_text(self, s)
+  }
+
+  fn read_text(&self) -> Result<Option<String>, ClipboardError> {
+    Clipboard::read_text(self)
+  }
+
+  fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
+    Clipboard::put_formats(self, formats)
+  }
+
+  fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    Clipboard::available_formats(self)
+  }
+}
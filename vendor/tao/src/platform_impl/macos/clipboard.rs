@@ -0,0 +1,129 @@
+// Copyright 2019-2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::clipboard::{ClipboardBackend, ClipboardFormat, FormatId};
+use cocoa::{
+  appkit::NSPasteboard,
+  base::{id, nil, BOOL, NO},
+  foundation::{NSArray, NSString, NSUInteger},
+};
+use objc::{msg_send, sel, sel_impl};
+use std::{fmt, sync::Mutex};
+
+#[derive(Debug)]
+pub struct ClipboardError(String);
+
+impl fmt::Display for ClipboardError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for ClipboardError {}
+
+// Caches the name we've leaked for each UTI pasteboard type `available_formats` has seen, so
+// repeated calls (e.g. a watcher inspecting formats on every clipboard change) don't re-leak it.
+static FORMAT_NAME_CACHE: Mutex<Vec<(String, FormatId)>> = Mutex::new(Vec::new());
+
+fn intern_format_name(name: String) -> FormatId {
+  let mut cache = FORMAT_NAME_CACHE.lock().unwrap();
+  if let Some((_, interned)) = cache.iter().find(|(cached, _)| *cached == name) {
+    return interned;
+  }
+  let interned: FormatId = Box::leak(name.clone().into_boxed_str());
+  cache.push((name, interned));
+  interned
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clipboard;
+
+impl Clipboard {
+  pub fn write_text(&mut self, s: impl AsRef<str>) -> Result<(), ClipboardError> {
+    let format: ClipboardFormat = s.as_ref().into();
+    self.put_formats(&[format])
+  }
+
+  pub fn read_text(&self) -> Result<Option<String>, ClipboardError> {
+    unsafe {
+      let pasteboard = NSPasteboard::generalPasteboard(nil);
+      let ty = NSString::alloc(nil).init_str(ClipboardFormat::TEXT);
+      let contents: id = msg_send![pasteboard, stringForType: ty];
+      if contents == nil {
+        return Ok(None);
+      }
+      Ok(Some(nsstring_to_string(contents)))
+    }
+  }
+
+  pub fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
+    unsafe {
+      let pasteboard = NSPasteboard::generalPasteboard(nil);
+      let () = msg_send![pasteboard, clearContents];
+
+      for format in formats {
+        let text = std::str::from_utf8(&format.data).map_err(|_| {
+          ClipboardError(format!(
+            "format '{}' isn't valid UTF-8 text; NSPasteboard string types require it",
+            format.identifier
+          ))
+        })?;
+        let ty = NSString::alloc(nil).init_str(format.identifier);
+        let value = NSString::alloc(nil).init_str(text);
+        let ok: BOOL = msg_send![pasteboard, setString: value forType: ty];
+        if ok == NO {
+          return Err(ClipboardError(format!(
+            "NSPasteboard rejected format '{}'",
+            format.identifier
+          )));
+        }
+      }
+
+      Ok(())
+    }
+  }
+
+  pub fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    unsafe {
+      let pasteboard = NSPasteboard::generalPasteboard(nil);
+      let types: id = msg_send![pasteboard, types];
+      if types == nil {
+        return Ok(Vec::new());
+      }
+      let count: NSUInteger = types.count();
+      let mut formats = Vec::with_capacity(count as usize);
+      for i in 0..count {
+        let ty: id = types.objectAtIndex(i);
+        formats.push(intern_format_name(nsstring_to_string(ty)));
+      }
+      Ok(formats)
+    }
+  }
+}
+
+impl ClipboardBackend for Clipboard {
+  type Error = ClipboardError;
+
+  fn write_text(&mut self, s: &str) -> Result<(), ClipboardError> {
+    Clipboard::write_text(self, s)
+  }
+
+  fn read_text(&self) -> Result<Option<String>, ClipboardError> {
+    Clipboard::read_text(self)
+  }
+
+  fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
+    Clipboard::put_formats(self, formats)
+  }
+
+  fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    Clipboard::available_formats(self)
+  }
+}
+
+unsafe fn nsstring_to_string(s: id) -> String {
+  let bytes = s.UTF8String() as *const u8;
+  let len: NSUInteger = msg_send![s, lengthOfBytesUsingEncoding: 4 /* NSUTF8StringEncoding */];
+  let slice = std::slice::from_raw_parts(bytes, len as usize);
+  String::from_utf8_lossy(slice).into_owned()
+}
@@ -1,117 +1,601 @@
 // Copyright 2019-2021 Tauri Programme within The Commons Conservancy
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::clipboard::{ClipboardFormat, FormatId};
+use crate::clipboard::{ClipboardBackend, ClipboardFormat, FormatId};
 use std::{
   ffi::{CString, OsStr},
+  fmt,
   os::windows::ffi::OsStrExt,
   ptr,
+  sync::{mpsc, Mutex},
+  thread,
+  time::Duration,
 };
 use winapi::{
   shared::{
-    minwindef::{FALSE, UINT},
+    minwindef::{DWORD, FALSE, LPARAM, LRESULT, UINT, WPARAM},
     ntdef::{CHAR, HANDLE, LPWSTR, WCHAR},
+    windef::HWND,
   },
   um::{
     errhandlingapi::GetLastError,
-    winbase::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+    libloaderapi::GetModuleHandleW,
+    winbase::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE},
+    wingdi::{BITMAPINFOHEADER, BI_RGB},
     winuser::{
-      CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, RegisterClipboardFormatA,
-      SetClipboardData, CF_UNICODETEXT,
+      AddClipboardFormatListener, CloseClipboard, CreateWindowExW, DefWindowProcW, DestroyWindow,
+      DispatchMessageW, EmptyClipboard, EnumClipboardFormats, GetClipboardData,
+      GetClipboardFormatNameW, GetClipboardSequenceNumber, GetMessageW, GetWindowLongPtrW,
+      OpenClipboard, PostMessageW, PostQuitMessage, RegisterClassW, RegisterClipboardFormatA,
+      RemoveClipboardFormatListener, SetClipboardData, SetWindowLongPtrW, TranslateMessage,
+      CF_DIB, CF_UNICODETEXT, CW_USEDEFAULT, GWLP_USERDATA, HWND_MESSAGE, MSG,
+      WM_CLIPBOARDUPDATE, WM_CLOSE, WM_DESTROY, WM_DESTROYCLIPBOARD, WM_RENDERALLFORMATS,
+      WM_RENDERFORMAT, WNDCLASSW,
     },
   },
 };
 
+/// An error from a Win32 clipboard operation, carrying the `GetLastError()` code (or other
+/// failure detail) so callers can decide whether to retry or surface a message to the user.
+#[derive(Debug)]
+pub enum ClipboardError {
+  /// `OpenClipboard` failed even after the retry budget in [`with_clipboard`] was exhausted.
+  OpenClipboard(DWORD),
+  /// `RegisterClipboardFormatA` failed for the given format identifier.
+  RegisterFormat { identifier: String, code: DWORD },
+  /// `SetClipboardData` failed for the given format identifier.
+  SetClipboardData { identifier: String, code: DWORD },
+  /// The format identifier contained an embedded null byte and can't be passed to Win32.
+  InvalidIdentifier(String),
+}
+
+impl fmt::Display for ClipboardError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ClipboardError::OpenClipboard(code) => {
+        write!(f, "failed to open the clipboard, error: {}", code)
+      }
+      ClipboardError::RegisterFormat { identifier, code } => write!(
+        f,
+        "failed to register clipboard format '{}', error: {}",
+        identifier, code
+      ),
+      ClipboardError::SetClipboardData { identifier, code } => write!(
+        f,
+        "failed to set clipboard data for format '{}', error: {}",
+        identifier, code
+      ),
+      ClipboardError::InvalidIdentifier(identifier) => {
+        write!(f, "clipboard format identifier '{}' contains a null byte", identifier)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ClipboardError {}
+
+/// A raw bitmap for clipboard cut-and-paste, e.g. a screenshot or a generated network-join QR
+/// code. Pixels are top-down, 8 bits per channel, in RGBA order.
+#[derive(Debug, Clone)]
+pub struct ClipboardImage {
+  pub width: u32,
+  pub height: u32,
+  pub rgba: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Clipboard;
 
 impl Clipboard {
-  pub fn write_text(&mut self, s: impl AsRef<str>) {
+  pub fn write_text(&mut self, s: impl AsRef<str>) -> Result<(), ClipboardError> {
     let s = s.as_ref();
     let format: ClipboardFormat = s.into();
     self.put_formats(&[format])
   }
 
-  pub(crate) fn read_text(&self) -> Option<String> {
+  pub(crate) fn read_text(&self) -> Result<Option<String>, ClipboardError> {
     with_clipboard(|| unsafe {
       let handle = GetClipboardData(CF_UNICODETEXT);
       if handle.is_null() {
-        None
-      } else {
-        let unic_str = GlobalLock(handle) as LPWSTR;
-        let mut len = 0;
-        while *unic_str.offset(len) != 0 {
-          len += 1;
-        }
-        let utf16_slice = std::slice::from_raw_parts(unic_str, len as usize);
-        let result = String::from_utf16(utf16_slice);
-        if let Ok(result) = result {
-          GlobalUnlock(handle);
-          return Some(result);
-        }
+        return None;
+      }
 
-        None
+      let unic_str = GlobalLock(handle) as LPWSTR;
+      let mut len = 0;
+      while *unic_str.offset(len) != 0 {
+        len += 1;
       }
+      let utf16_slice = std::slice::from_raw_parts(unic_str, len as usize);
+      let result = String::from_utf16(utf16_slice).ok();
+      GlobalUnlock(handle);
+      result
     })
-    .flatten()
   }
 
-  pub(crate) fn put_formats(&mut self, formats: &[ClipboardFormat]) {
+  pub(crate) fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
     with_clipboard(|| unsafe {
       EmptyClipboard();
 
       for format in formats {
+        let format_id = get_format_id(&format.identifier)?;
         let handle = make_handle(&format);
-        let format_id = match get_format_id(&format.identifier) {
-          Some(id) => id,
-          None => {
-            println!("failed to register clipboard format {}", &format.identifier);
-            continue;
-          }
-        };
         let result = SetClipboardData(format_id, handle);
         if result.is_null() {
-          println!(
-            "failed to set clipboard for fmt {}, error: {}",
-            &format.identifier,
-            GetLastError()
-          );
+          return Err(ClipboardError::SetClipboardData {
+            identifier: format.identifier.to_string(),
+            code: GetLastError(),
+          });
+        }
+      }
+
+      Ok(())
+    })?
+  }
+
+  /// Places an image on the clipboard as a device-independent bitmap (`CF_DIB`).
+  pub fn write_image(&mut self, image: &ClipboardImage) -> Result<(), ClipboardError> {
+    with_clipboard(|| unsafe {
+      EmptyClipboard();
+
+      let handle = make_dib_handle(image);
+      let result = SetClipboardData(CF_DIB, handle);
+      if result.is_null() {
+        return Err(ClipboardError::SetClipboardData {
+          identifier: "CF_DIB".to_string(),
+          code: GetLastError(),
+        });
+      }
+
+      Ok(())
+    })?
+  }
+
+  /// Reads a device-independent bitmap (`CF_DIB`) off the clipboard, if present. Returns
+  /// `Ok(None)` if the clipboard holds no `CF_DIB` data, or the data is a compressed (BI_RLE)
+  /// or otherwise unsupported DIB variant.
+  pub fn read_image(&self) -> Result<Option<ClipboardImage>, ClipboardError> {
+    with_clipboard(|| unsafe {
+      let handle = GetClipboardData(CF_DIB);
+      if handle.is_null() {
+        return None;
+      }
+
+      let available = GlobalSize(handle);
+      if available < std::mem::size_of::<BITMAPINFOHEADER>() {
+        return None;
+      }
+
+      let base = GlobalLock(handle) as *const u8;
+      if base.is_null() {
+        return None;
+      }
+      let header = &*(base as *const BITMAPINFOHEADER);
+      let result = parse_dib(header, base, available);
+      GlobalUnlock(handle);
+      result
+    })
+  }
+
+  /// Lists every format currently present on the clipboard, not just `CF_UNICODETEXT`. Standard
+  /// formats (`CF_TEXT`, `CF_DIB`, ...) are named from [`STANDARD_FORMATS`]; registered formats
+  /// are named via `GetClipboardFormatNameW`.
+  pub fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    with_clipboard(|| unsafe {
+      let mut formats = Vec::new();
+      let mut format_id = EnumClipboardFormats(0);
+      while format_id != 0 {
+        formats.push(format_name(format_id));
+        format_id = EnumClipboardFormats(format_id);
+      }
+      formats
+    })
+  }
+
+  /// Reads the raw bytes for an arbitrary clipboard format, e.g. HTML, RTF, or a custom
+  /// ZeroTier format, rather than being limited to Unicode text.
+  pub fn read_format(&self, id: FormatId) -> Result<Option<Vec<u8>>, ClipboardError> {
+    let format_id = get_format_id(id)?;
+    with_clipboard(|| unsafe {
+      let handle = GetClipboardData(format_id);
+      if handle.is_null() {
+        return None;
+      }
+
+      let size = GlobalSize(handle);
+      let locked = GlobalLock(handle) as *const u8;
+      if locked.is_null() {
+        return None;
+      }
+      let data = std::slice::from_raw_parts(locked, size).to_vec();
+      GlobalUnlock(handle);
+      Some(data)
+    })
+  }
+
+  /// Claims clipboard ownership and advertises `formats` without rendering any of their data
+  /// yet, per the Win32 delayed-rendering protocol. Each closure is only invoked once another
+  /// application actually requests its format (`WM_RENDERFORMAT`) or right before this process
+  /// loses clipboard ownership (`WM_RENDERALLFORMATS`), which avoids materializing large
+  /// payloads (e.g. megabytes of image data) until they're actually pasted. Returns once
+  /// ownership has been claimed; the closures are serviced on a dedicated background thread for
+  /// as long as this process keeps clipboard ownership, and are dropped once it's lost
+  /// (`WM_DESTROYCLIPBOARD`).
+  pub fn put_formats_delayed(
+    formats: Vec<(FormatId, Box<dyn Fn() -> Vec<u8> + Send>)>,
+  ) -> Result<(), ClipboardError> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || unsafe { run_delayed_render_thread(formats, tx) });
+    rx.recv()
+      .expect("delayed render thread exited before claiming the clipboard")
+  }
+
+  /// Spawns a background thread that watches the system clipboard and invokes `callback`
+  /// every time its contents change. The returned [`WatchHandle`] keeps the watcher alive;
+  /// dropping it tears down the watcher thread's window.
+  pub fn watch(callback: impl FnMut(&Clipboard) + Send + 'static) -> WatchHandle {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || unsafe { run_watcher_thread(callback, tx) });
+    let hwnd = rx
+      .recv()
+      .expect("clipboard watcher thread exited before creating its window");
+    WatchHandle { hwnd: SendHwnd(hwnd) }
+  }
+}
+
+impl ClipboardBackend for Clipboard {
+  type Error = ClipboardError;
+
+  fn write_text(&mut self, s: &str) -> Result<(), ClipboardError> {
+    Clipboard::write_text(self, s)
+  }
+
+  fn read_text(&self) -> Result<Option<String>, ClipboardError> {
+    Clipboard::read_text(self)
+  }
+
+  fn put_formats(&mut self, formats: &[ClipboardFormat]) -> Result<(), ClipboardError> {
+    Clipboard::put_formats(self, formats)
+  }
+
+  fn available_formats(&self) -> Result<Vec<FormatId>, ClipboardError> {
+    Clipboard::available_formats(self)
+  }
+}
+
+/// Keeps a clipboard watcher spawned by [`Clipboard::watch`] alive. Dropping this removes the
+/// clipboard format listener and destroys the watcher's message-only window.
+pub struct WatchHandle {
+  hwnd: SendHwnd,
+}
+
+impl Drop for WatchHandle {
+  fn drop(&mut self) {
+    unsafe {
+      RemoveClipboardFormatListener(self.hwnd.0);
+      // DestroyWindow must run on the thread that created the window (the watcher thread), not
+      // whichever thread drops this handle, so ask it to destroy its own window instead of
+      // calling DestroyWindow directly.
+      PostMessageW(self.hwnd.0, WM_CLOSE, 0, 0);
+    }
+  }
+}
+
+// `HWND` is just a `*mut c_void`, but it's only ever touched from the watcher thread (where it's
+// created) and from `Drop`, which only reads the value to pass it back to the Win32 API.
+struct SendHwnd(HWND);
+unsafe impl Send for SendHwnd {}
+
+struct WatcherState {
+  callback: Box<dyn FnMut(&Clipboard) + Send>,
+  last_seq: u32,
+  // AddClipboardFormatListener can deliver an initial WM_CLIPBOARDUPDATE for the clipboard's
+  // current contents; skip that one so callers only see genuine changes.
+  first_update_seen: bool,
+}
+
+unsafe fn run_watcher_thread(
+  callback: impl FnMut(&Clipboard) + Send + 'static,
+  ready: mpsc::Sender<HWND>,
+) {
+  let class_name = wide_null("ZeroTierDesktopUIClipboardWatcher");
+  let hinstance = GetModuleHandleW(ptr::null());
+
+  let wndclass = WNDCLASSW {
+    style: 0,
+    lpfnWndProc: Some(watcher_wndproc),
+    cbClsExtra: 0,
+    cbWndExtra: 0,
+    hInstance: hinstance,
+    hIcon: ptr::null_mut(),
+    hCursor: ptr::null_mut(),
+    hbrBackground: ptr::null_mut(),
+    lpszMenuName: ptr::null(),
+    lpszClassName: class_name.as_ptr(),
+  };
+  // Registration fails if a previous watcher already registered this class; that's fine, we
+  // can still create windows of it.
+  RegisterClassW(&wndclass);
+
+  let hwnd = CreateWindowExW(
+    0,
+    class_name.as_ptr(),
+    ptr::null(),
+    0,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    HWND_MESSAGE,
+    ptr::null_mut(),
+    hinstance,
+    ptr::null_mut(),
+  );
+
+  let state = Box::new(WatcherState {
+    callback: Box::new(callback),
+    last_seq: GetClipboardSequenceNumber(),
+    first_update_seen: false,
+  });
+  SetWindowLongPtrW(hwnd, GWLP_USERDATA, Box::into_raw(state) as isize);
+
+  AddClipboardFormatListener(hwnd);
+
+  if ready.send(hwnd).is_err() {
+    // Caller already dropped its receiver; nothing more to do.
+    return;
+  }
+
+  let mut msg: MSG = std::mem::zeroed();
+  while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+    TranslateMessage(&msg);
+    DispatchMessageW(&msg);
+  }
+}
+
+unsafe extern "system" fn watcher_wndproc(
+  hwnd: HWND,
+  msg: UINT,
+  wparam: WPARAM,
+  lparam: LPARAM,
+) -> LRESULT {
+  match msg {
+    WM_CLIPBOARDUPDATE => {
+      let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut WatcherState;
+      if let Some(state) = state.as_mut() {
+        let seq = GetClipboardSequenceNumber();
+        if !state.first_update_seen {
+          state.first_update_seen = true;
+          state.last_seq = seq;
+        } else if seq != state.last_seq {
+          state.last_seq = seq;
+          (state.callback)(&Clipboard);
         }
       }
-    });
+      0
+    }
+    WM_CLOSE => {
+      DestroyWindow(hwnd);
+      0
+    }
+    WM_DESTROY => {
+      let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut WatcherState;
+      if !state.is_null() {
+        drop(Box::from_raw(state));
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+      }
+      PostQuitMessage(0);
+      0
+    }
+    _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+  }
+}
+
+fn wide_null(s: &str) -> Vec<u16> {
+  OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+struct DelayedRenderState {
+  formats: Vec<(UINT, Box<dyn Fn() -> Vec<u8> + Send>)>,
+}
+
+unsafe fn run_delayed_render_thread(
+  formats: Vec<(FormatId, Box<dyn Fn() -> Vec<u8> + Send>)>,
+  ready: mpsc::Sender<Result<(), ClipboardError>>,
+) {
+  let resolved = match formats
+    .into_iter()
+    .map(|(id, render)| get_format_id(id).map(|format_id| (format_id, render)))
+    .collect::<Result<Vec<_>, _>>()
+  {
+    Ok(resolved) => resolved,
+    Err(err) => {
+      ready.send(Err(err)).ok();
+      return;
+    }
+  };
+
+  let class_name = wide_null("ZeroTierDesktopUIClipboardDelayedRender");
+  let hinstance = GetModuleHandleW(ptr::null());
+
+  let wndclass = WNDCLASSW {
+    style: 0,
+    lpfnWndProc: Some(delayed_render_wndproc),
+    cbClsExtra: 0,
+    cbWndExtra: 0,
+    hInstance: hinstance,
+    hIcon: ptr::null_mut(),
+    hCursor: ptr::null_mut(),
+    hbrBackground: ptr::null_mut(),
+    lpszMenuName: ptr::null(),
+    lpszClassName: class_name.as_ptr(),
+  };
+  RegisterClassW(&wndclass);
+
+  let hwnd = CreateWindowExW(
+    0,
+    class_name.as_ptr(),
+    ptr::null(),
+    0,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    CW_USEDEFAULT,
+    HWND_MESSAGE,
+    ptr::null_mut(),
+    hinstance,
+    ptr::null_mut(),
+  );
+
+  if let Err(err) = claim_clipboard(hwnd, &resolved) {
+    DestroyWindow(hwnd);
+    ready.send(Err(err)).ok();
+    return;
+  }
+
+  let state = Box::new(DelayedRenderState { formats: resolved });
+  SetWindowLongPtrW(hwnd, GWLP_USERDATA, Box::into_raw(state) as isize);
+
+  if ready.send(Ok(())).is_err() {
+    // Caller already dropped its receiver; still keep servicing render requests until we lose
+    // clipboard ownership, since other applications may still be relying on the advertised data.
+  }
+
+  let mut msg: MSG = std::mem::zeroed();
+  while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+    TranslateMessage(&msg);
+    DispatchMessageW(&msg);
+  }
+}
+
+unsafe fn claim_clipboard(
+  hwnd: HWND,
+  formats: &[(UINT, Box<dyn Fn() -> Vec<u8> + Send>)],
+) -> Result<(), ClipboardError> {
+  let mut last_error = 0;
+  for attempt in 0..OPEN_CLIPBOARD_ATTEMPTS {
+    if OpenClipboard(hwnd) != FALSE {
+      EmptyClipboard();
+      for (format_id, _) in formats {
+        SetClipboardData(*format_id, ptr::null_mut());
+      }
+      CloseClipboard();
+      return Ok(());
+    }
+    last_error = GetLastError();
+    if attempt + 1 < OPEN_CLIPBOARD_ATTEMPTS {
+      thread::sleep(OPEN_CLIPBOARD_RETRY_DELAY);
+    }
+  }
+  Err(ClipboardError::OpenClipboard(last_error))
+}
+
+unsafe extern "system" fn delayed_render_wndproc(
+  hwnd: HWND,
+  msg: UINT,
+  wparam: WPARAM,
+  lparam: LPARAM,
+) -> LRESULT {
+  match msg {
+    WM_RENDERFORMAT => {
+      // The clipboard is already open for this window while it handles WM_RENDERFORMAT.
+      let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut DelayedRenderState;
+      if let Some(state) = state.as_ref() {
+        let requested = wparam as UINT;
+        if let Some((_, render)) = state.formats.iter().find(|(id, _)| *id == requested) {
+          let handle = copy_bytes_to_global(&render());
+          SetClipboardData(requested, handle);
+        }
+      }
+      0
+    }
+    WM_RENDERALLFORMATS => {
+      let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut DelayedRenderState;
+      if let Some(state) = state.as_ref() {
+        if OpenClipboard(hwnd) != FALSE {
+          for (format_id, render) in &state.formats {
+            let handle = copy_bytes_to_global(&render());
+            SetClipboardData(*format_id, handle);
+          }
+          CloseClipboard();
+        }
+      }
+      0
+    }
+    WM_DESTROYCLIPBOARD => {
+      // We've lost clipboard ownership to another application; the render closures (and
+      // whatever they'd capture) are no longer needed.
+      let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut DelayedRenderState;
+      if !state.is_null() {
+        drop(Box::from_raw(state));
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+      }
+      DestroyWindow(hwnd);
+      0
+    }
+    WM_DESTROY => {
+      PostQuitMessage(0);
+      0
+    }
+    _ => DefWindowProcW(hwnd, msg, wparam, lparam),
   }
 }
 
-fn get_format_id(format: FormatId) -> Option<UINT> {
+// Caches names for registered (non-standard) formats discovered via `available_formats`, so
+// repeated lookups of the same format id don't re-query the OS or re-leak a string.
+static FORMAT_NAME_CACHE: Mutex<Vec<(UINT, &'static str)>> = Mutex::new(Vec::new());
+
+fn format_name(format_id: UINT) -> FormatId {
+  if let Some((_, name)) = STANDARD_FORMATS.iter().find(|(id, _)| *id == format_id) {
+    return name;
+  }
+
+  let mut cache = FORMAT_NAME_CACHE.lock().unwrap();
+  if let Some((_, name)) = cache.iter().find(|(id, _)| *id == format_id) {
+    return name;
+  }
+
+  let mut buf = [0u16; 256];
+  let len = unsafe { GetClipboardFormatNameW(format_id, buf.as_mut_ptr(), buf.len() as i32) };
+  let name: &'static str = if len > 0 {
+    Box::leak(String::from_utf16_lossy(&buf[..len as usize]).into_boxed_str())
+  } else {
+    Box::leak(format!("CF_UNKNOWN_{}", format_id).into_boxed_str())
+  };
+  cache.push((format_id, name));
+  name
+}
+
+fn get_format_id(format: FormatId) -> Result<UINT, ClipboardError> {
   if let Some((id, _)) = STANDARD_FORMATS.iter().find(|(_, s)| s == &format) {
-    return Some(*id);
+    return Ok(*id);
+  }
+  // `format_name` synthesizes this for private (numeric-only) formats it can't look up a real
+  // name for; resolve it back to that id directly instead of registering a new format under
+  // the literal synthetic string, which would silently read the wrong data.
+  if let Some(id) = format
+    .strip_prefix("CF_UNKNOWN_")
+    .and_then(|id| id.parse::<UINT>().ok())
+  {
+    return Ok(id);
   }
   match format {
-    ClipboardFormat::TEXT => Some(CF_UNICODETEXT),
+    ClipboardFormat::TEXT => Ok(CF_UNICODETEXT),
     other => register_identifier(other),
   }
 }
 
-fn register_identifier(ident: &str) -> Option<UINT> {
-  let cstr = match CString::new(ident) {
-    Ok(s) => s,
-    Err(_) => {
-      // granted this should happen _never_, but unwrap feels bad
-      println!("Null byte in clipboard identifier '{}'", ident);
-      return None;
-    }
-  };
+fn register_identifier(ident: &str) -> Result<UINT, ClipboardError> {
+  // granted a null byte should happen _never_, but unwrap feels bad
+  let cstr = CString::new(ident).map_err(|_| ClipboardError::InvalidIdentifier(ident.to_string()))?;
   unsafe {
     let pb_format = RegisterClipboardFormatA(cstr.as_ptr());
     if pb_format == 0 {
-      let err = GetLastError();
-      println!(
-        "failed to register clipboard format '{}'; error {}.",
-        ident, err
-      );
-      return None;
+      return Err(ClipboardError::RegisterFormat {
+        identifier: ident.to_string(),
+        code: GetLastError(),
+      });
     }
-    Some(pb_format)
+    Ok(pb_format)
   }
 }
 
@@ -125,28 +609,143 @@ unsafe fn make_handle(format: &ClipboardFormat) -> HANDLE {
     GlobalUnlock(handle);
     handle
   } else {
-    let handle = GlobalAlloc(
-      GMEM_MOVEABLE,
-      format.data.len() * std::mem::size_of::<CHAR>(),
-    );
-    let locked = GlobalLock(handle) as *mut u8;
-    ptr::copy_nonoverlapping(format.data.as_ptr(), locked, format.data.len());
-    GlobalUnlock(handle);
-    handle
+    copy_bytes_to_global(&format.data)
   }
 }
 
-fn with_clipboard<V>(f: impl FnOnce() -> V) -> Option<V> {
-  unsafe {
-    if OpenClipboard(ptr::null_mut()) == FALSE {
-      return None;
+unsafe fn copy_bytes_to_global(data: &[u8]) -> HANDLE {
+  let handle = GlobalAlloc(GMEM_MOVEABLE, data.len() * std::mem::size_of::<CHAR>());
+  let locked = GlobalLock(handle) as *mut u8;
+  ptr::copy_nonoverlapping(data.as_ptr(), locked, data.len());
+  GlobalUnlock(handle);
+  handle
+}
+
+// Another process (Office, a browser, ...) routinely holds the clipboard open for a few
+// milliseconds, so a single failed `OpenClipboard` isn't treated as fatal.
+const OPEN_CLIPBOARD_ATTEMPTS: u32 = 5;
+const OPEN_CLIPBOARD_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// Builds a packed DIB (BITMAPINFOHEADER followed immediately by pixel data, as `CF_DIB` expects)
+// from a top-down RGBA image. DIBs are conventionally stored bottom-up, so the row order is
+// flipped here; pixels are written as BGRA (with the alpha byte carried through, even though
+// most consumers of 32bpp BI_RGB ignore it).
+unsafe fn make_dib_handle(image: &ClipboardImage) -> HANDLE {
+  let header_size = std::mem::size_of::<BITMAPINFOHEADER>();
+  let stride = image.width as usize * 4;
+  let pixel_bytes = stride * image.height as usize;
+
+  let header = BITMAPINFOHEADER {
+    biSize: header_size as u32,
+    biWidth: image.width as i32,
+    biHeight: image.height as i32, // positive height: bottom-up DIB
+    biPlanes: 1,
+    biBitCount: 32,
+    biCompression: BI_RGB,
+    biSizeImage: pixel_bytes as u32,
+    biXPelsPerMeter: 0,
+    biYPelsPerMeter: 0,
+    biClrUsed: 0,
+    biClrImportant: 0,
+  };
+
+  let handle = GlobalAlloc(GMEM_MOVEABLE, header_size + pixel_bytes);
+  let locked = GlobalLock(handle) as *mut u8;
+  ptr::copy_nonoverlapping(&header as *const _ as *const u8, locked, header_size);
+
+  let pixels = locked.add(header_size);
+  for row in 0..image.height as usize {
+    let src = &image.rgba[row * stride..row * stride + stride];
+    let dst_row = image.height as usize - 1 - row;
+    let dst = pixels.add(dst_row * stride);
+    for (i, px) in src.chunks_exact(4).enumerate() {
+      *dst.add(i * 4) = px[2]; // B
+      *dst.add(i * 4 + 1) = px[1]; // G
+      *dst.add(i * 4 + 2) = px[0]; // R
+      *dst.add(i * 4 + 3) = px[3]; // A
     }
+  }
+
+  GlobalUnlock(handle);
+  handle
+}
+
+// Parses a `CF_DIB` payload (BITMAPINFOHEADER + pixel data) back into top-down RGBA. Handles
+// both 24bpp and 32bpp uncompressed (BI_RGB) source bitmaps, top-down or bottom-up; anything
+// else (BI_RLE4/BI_RLE8 compression, other bit depths) is rejected rather than misread.
+// `available` is the total size of the global memory block `base` points into (from
+// `GlobalSize`), used to reject a malformed or truncated DIB before reading out of bounds.
+unsafe fn parse_dib(
+  header: &BITMAPINFOHEADER,
+  base: *const u8,
+  available: usize,
+) -> Option<ClipboardImage> {
+  if header.biCompression != BI_RGB {
+    return None;
+  }
+  let bytes_per_pixel = match header.biBitCount {
+    24 => 3,
+    32 => 4,
+    _ => return None,
+  };
+  if header.biSize as usize > available {
+    return None;
+  }
+
+  let width = header.biWidth.unsigned_abs() as usize;
+  let height = header.biHeight.unsigned_abs() as usize;
+  let top_down = header.biHeight < 0;
+  // DIB rows are padded to a 4-byte boundary.
+  let stride = (width * bytes_per_pixel + 3) & !3;
+
+  let pixel_data_offset = header.biSize as usize;
+  let pixel_bytes = stride.checked_mul(height)?;
+  if pixel_bytes > available - pixel_data_offset {
+    return None;
+  }
 
-    let result = f();
+  let pixels = base.add(pixel_data_offset);
+  let mut rgba = vec![0u8; width * height * 4];
 
-    CloseClipboard();
+  for row in 0..height {
+    let src_row = if top_down { row } else { height - 1 - row };
+    let src = pixels.add(src_row * stride);
+    for col in 0..width {
+      let px = src.add(col * bytes_per_pixel);
+      let (b, g, r) = (*px, *px.add(1), *px.add(2));
+      let a = if bytes_per_pixel == 4 { *px.add(3) } else { 255 };
+      let dst = (row * width + col) * 4;
+      rgba[dst] = r;
+      rgba[dst + 1] = g;
+      rgba[dst + 2] = b;
+      rgba[dst + 3] = a;
+    }
+  }
+
+  Some(ClipboardImage {
+    width: width as u32,
+    height: height as u32,
+    rgba,
+  })
+}
+
+fn with_clipboard<V>(f: impl FnOnce() -> V) -> Result<V, ClipboardError> {
+  unsafe {
+    let mut last_error = 0;
+    for attempt in 0..OPEN_CLIPBOARD_ATTEMPTS {
+      if OpenClipboard(ptr::null_mut()) != FALSE {
+        let result = f();
+        CloseClipboard();
+        return Ok(result);
+      }
+
+      last_error = GetLastError();
+      if attempt + 1 < OPEN_CLIPBOARD_ATTEMPTS {
+        thread::sleep(OPEN_CLIPBOARD_RETRY_DELAY);
+      }
+    }
 
-    Some(result)
+    Err(ClipboardError::OpenClipboard(last_error))
   }
 }
 